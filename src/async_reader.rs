@@ -0,0 +1,360 @@
+//! An async counterpart to [`Reader`](crate::reader::Reader), for embedding a qcow2
+//! image in an async VMM or streaming pipeline without blocking the executor on every
+//! cluster fetch.
+//!
+//! Gated behind the `tokio` feature, since it's the only consumer of this module that
+//! needs an async runtime in its dependency tree.
+#![cfg(feature = "tokio")]
+
+use crate::*;
+use crate::levels::{L2Entry, l2_entries_per_table};
+
+use std::convert::TryInto;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+
+/// The future backing [`AsyncReader::pending`](AsyncReader), resolving to a decoded
+/// cluster's contents. Factored out purely so the type doesn't have to be spelled out
+/// twice (in the field and in [`AsyncReader::start_fetch`]'s return type).
+type PendingFetch = Pin<Box<dyn std::future::Future<Output = io::Result<Box<[u8]>>> + Send>>;
+
+/// An async, positioned-read backend for an [`AsyncReader`]. Lets the host data come
+/// from a file, a network socket, or anything else that can serve "read N bytes at
+/// offset P", the same way `pread(2)` doesn't need a shared cursor to be mutated.
+#[async_trait::async_trait]
+pub trait AsyncPositionedRead: Send + Sync {
+    async fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<()>;
+}
+
+/// An async, seekable reader over the guest virtual drive, modeled as a sequence of
+/// fixed-size cluster chunks: bytes are served out of the currently decoded cluster,
+/// and the next cluster is fetched (and decoded) asynchronously once the cursor runs
+/// past its end.
+pub struct AsyncReader<'qcow, B>
+where B: AsyncPositionedRead + 'static,
+{
+    qcow: &'qcow Qcow2,
+    backend: Arc<B>,
+
+    /// current position of the reader within the guest
+    pos: u64,
+
+    /// index (not offset) of the cluster currently decoded into `current_cluster`, or
+    /// `u64::MAX` if nothing has been decoded yet
+    current_cluster_index: u64,
+    current_cluster: Box<[u8]>,
+
+    /// an in-flight L1→L2 lookup plus cluster fetch/decode for the cluster `poll_read`
+    /// or `poll_complete` is waiting on, or `None` if the reader is idle
+    pending: Option<PendingFetch>,
+}
+
+impl<'qcow, B> AsyncReader<'qcow, B>
+where B: AsyncPositionedRead + 'static,
+{
+    /// Create an async reader for reading from the guest virtual drive.
+    pub fn new(qcow: &'qcow Qcow2, backend: B) -> Self {
+        let cluster_size = qcow.cluster_size() as usize;
+        AsyncReader {
+            qcow,
+            backend: Arc::new(backend),
+            pos: 0,
+            current_cluster_index: u64::MAX,
+            current_cluster: vec![0; cluster_size].into_boxed_slice(),
+            pending: None,
+        }
+    }
+
+    /// Returns the current read position within the guest virtual hard disk
+    pub fn guest_pos(&self) -> u64 {
+        self.pos
+    }
+
+    fn cluster_size(&self) -> u64 {
+        self.qcow.cluster_size()
+    }
+
+    fn cluster_index_at(&self, pos: u64) -> u64 {
+        pos / self.cluster_size()
+    }
+
+    /// Build the future that resolves `cluster_index` to its decoded contents: look up
+    /// its L1 entry (already decoded in memory), fetch and decode the L2 table it
+    /// points to, then fetch (or zero-fill) the data cluster the resulting L2 entry
+    /// describes. Everything the future touches is owned or `Arc`-shared so it doesn't
+    /// borrow from `self`, letting it be polled across multiple `poll_read` calls.
+    ///
+    /// **Note:** unlike the synchronous [`Reader`](crate::reader::Reader), this does not
+    /// consult [`Qcow2::backing`](crate::Qcow2) for unallocated/zero clusters — the
+    /// backing chain is built on the synchronous [`GuestRead`](crate::backing::GuestRead)
+    /// trait, which has no async counterpart yet. Rather than silently returning zeros
+    /// for guest ranges that should come from a backing image, an image whose backing
+    /// chain has been opened (so [`Qcow2::backing`](crate::Qcow2) is set, e.g. by
+    /// [`Qcow2::open_with_backing`]) fails fast with an explicit error instead. An image
+    /// whose backing chain was never opened reads such clusters as zeros, as the
+    /// synchronous reader does.
+    fn start_fetch(
+        &self, cluster_index: u64,
+    ) -> PendingFetch {
+        let cluster_size = self.cluster_size();
+        let cluster_size_usize = cluster_size as usize;
+        let l2_entries = l2_entries_per_table(cluster_size);
+        let l1_key = cluster_index / l2_entries;
+        let l2_index = (cluster_index % l2_entries) as usize;
+        let backend = Arc::clone(&self.backend);
+        let has_backing = self.qcow.backing.borrow().is_some();
+
+        let l1_entry = self.qcow.l1_table.get(l1_key as usize).copied();
+
+        Box::pin(async move {
+            let backing_unsupported = || io::Error::new(
+                io::ErrorKind::Unsupported,
+                "AsyncReader cannot read an unallocated/zero cluster of an overlay image: \
+                 backing-chain reads are not yet supported on the async path",
+            );
+
+            let l1_entry = l1_entry.ok_or_else(|| io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Read position past end of virtual disk"
+            ))?;
+
+            if !l1_entry.is_allocated() {
+                if has_backing {
+                    return Err(backing_unsupported());
+                }
+                return Ok(vec![0u8; cluster_size_usize].into_boxed_slice());
+            }
+
+            let mut l2_table = vec![0u8; cluster_size_usize];
+            backend.read_at(l1_entry.l2_offset, &mut l2_table).await?;
+
+            let entry_start = l2_index * std::mem::size_of::<u64>();
+            let raw = u64::from_be_bytes(
+                l2_table[entry_start..entry_start + 8].try_into().unwrap()
+            );
+            let l2_entry = L2Entry::from_raw(raw);
+
+            if !l2_entry.is_allocated() && has_backing {
+                return Err(backing_unsupported());
+            }
+
+            let mut cluster = vec![0u8; cluster_size_usize].into_boxed_slice();
+            if l2_entry.is_allocated() {
+                backend.read_at(l2_entry.cluster_offset(), &mut cluster).await?;
+            }
+
+            Ok(cluster)
+        })
+    }
+
+    /// Drive the async fetch-and-decode of `cluster_index` into `current_cluster`.
+    fn poll_fill_cluster(
+        &mut self, cx: &mut Context<'_>, cluster_index: u64,
+    ) -> Poll<io::Result<()>> {
+        if self.current_cluster_index == cluster_index {
+            return Poll::Ready(Ok(()));
+        }
+
+        if self.pending.is_none() {
+            self.pending = Some(self.start_fetch(cluster_index));
+        }
+
+        let pending = self.pending.as_mut().unwrap();
+        match pending.as_mut().poll(cx) {
+            Poll::Ready(Ok(cluster)) => {
+                self.pending = None;
+                self.current_cluster = cluster;
+                self.current_cluster_index = cluster_index;
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(e)) => {
+                self.pending = None;
+                Poll::Ready(Err(e))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<'qcow, B> AsyncRead for AsyncReader<'qcow, B>
+where B: AsyncPositionedRead + 'static,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let cluster_size = self.cluster_size();
+        let cluster_index = self.cluster_index_at(self.pos);
+
+        match self.as_mut().poll_fill_cluster(cx, cluster_index) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        }
+
+        let pos_in_cluster = (self.pos % cluster_size) as usize;
+        let available = &self.current_cluster[pos_in_cluster..];
+        let read_len = usize::min(available.len(), buf.remaining());
+
+        buf.put_slice(&available[..read_len]);
+        self.pos += read_len as u64;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<'qcow, B> AsyncSeek for AsyncReader<'qcow, B>
+where B: AsyncPositionedRead + 'static,
+{
+    fn start_seek(mut self: Pin<&mut Self>, pos: io::SeekFrom) -> io::Result<()> {
+        let new_pos = match pos {
+            io::SeekFrom::Start(p) => p,
+            io::SeekFrom::Current(offset) => {
+                ((self.pos as i128) + (offset as i128)).try_into()
+                    .map_err(|_| io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "seek out of range of 64-bit position"
+                    ))?
+            }
+            io::SeekFrom::End(offset) => {
+                ((self.qcow.header.size as i128) + (offset as i128)).try_into()
+                    .map_err(|_| io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "seek out of range of 64-bit position"
+                    ))?
+            }
+        };
+
+        // a seek always lands on a cluster the reader hasn't necessarily decoded yet;
+        // `poll_complete` resolves it the same way `update_l2_cache` does synchronously
+        self.pos = new_pos;
+        self.pending = None;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let pos = self.pos;
+        let cluster_index = self.cluster_index_at(pos);
+        let mut this = self;
+
+        match this.as_mut().poll_fill_cluster(cx, cluster_index) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(pos)),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::levels::L1Entry;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    /// An in-memory `AsyncPositionedRead` backend over a fixed byte buffer, standing in
+    /// for a real file or socket.
+    struct MemoryBackend(Vec<u8>);
+
+    #[async_trait::async_trait]
+    impl AsyncPositionedRead for MemoryBackend {
+        async fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<()> {
+            let pos = pos as usize;
+            let readable = usize::min(buf.len(), self.0.len().saturating_sub(pos));
+            buf[..readable].copy_from_slice(&self.0[pos..pos + readable]);
+            buf[readable..].fill(0);
+            Ok(())
+        }
+    }
+
+    /// Same three-cluster host layout as the synchronous extent tests: host cluster 0
+    /// reserved, cluster 1 the L2 table, cluster 2 real data.
+    fn new_fixture(cluster_size: usize, data: &[u8]) -> (Qcow2, MemoryBackend) {
+        let l2_table_offset = cluster_size as u64;
+        let data_cluster_offset = 2 * cluster_size as u64;
+
+        let mut buf = vec![0u8; cluster_size * 3];
+        buf[2 * cluster_size..2 * cluster_size + data.len()].copy_from_slice(data);
+
+        let raw = data_cluster_offset | crate::levels::OFLAG_COPIED;
+        let l2_start = cluster_size;
+        buf[l2_start..l2_start + 8].copy_from_slice(&raw.to_be_bytes());
+
+        let header = Header {
+            version: 2,
+            cluster_bits: cluster_size.trailing_zeros(),
+            size: 3 * cluster_size as u64,
+            l1_size: 1,
+            l1_table_offset: 0,
+            refcount_table_offset: 0,
+            refcount_table_clusters: 0,
+            refcount_order: 4,
+            backing_file_name: None,
+            v3_header: None,
+        };
+
+        let l1_table = vec![L1Entry { l2_offset: l2_table_offset }];
+        (Qcow2::from_parts(header, l1_table), MemoryBackend(buf))
+    }
+
+    #[tokio::test]
+    async fn reads_an_allocated_cluster_through_the_backend() {
+        let mut data = vec![0u8; 512];
+        data[..5].copy_from_slice(b"qcow!");
+        let (qcow, backend) = new_fixture(512, &data);
+
+        let mut reader = AsyncReader::new(&qcow, backend);
+        let mut buf = [0u8; 5];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"qcow!");
+    }
+
+    #[tokio::test]
+    async fn reads_zero_for_an_unallocated_cluster() {
+        let (qcow, backend) = new_fixture(512, &[]);
+        // leave the only L1 entry without an L2 table so every cluster is unallocated
+        let qcow = Qcow2::from_parts(qcow.header, vec![L1Entry::default()]);
+
+        let mut reader = AsyncReader::new(&qcow, backend);
+        let mut buf = [0xffu8; 512];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf[..], &[0u8; 512][..]);
+    }
+
+    /// A `GuestRead` that should never actually be consulted by `AsyncReader` today;
+    /// its presence alone is enough to make an unallocated/zero cluster read fail.
+    struct UnusedBacking;
+
+    impl crate::backing::GuestRead for UnusedBacking {
+        fn read_at(&mut self, _pos: u64, buf: &mut [u8]) -> io::Result<()> {
+            buf.fill(0xff);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn overlay_image_errors_instead_of_silently_reading_zero() {
+        let (qcow, backend) = new_fixture(512, &[]);
+        // leave the only L1 entry without an L2 table so every cluster is unallocated
+        let qcow = Qcow2::from_parts(qcow.header, vec![L1Entry::default()]);
+        *qcow.backing.borrow_mut() = Some(Box::new(UnusedBacking));
+
+        let mut reader = AsyncReader::new(&qcow, backend);
+        let mut buf = [0u8; 512];
+        let err = reader.read_exact(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[tokio::test]
+    async fn seek_moves_to_the_right_cluster() {
+        let mut data = vec![0u8; 512];
+        data[..5].copy_from_slice(b"qcow!");
+        let (qcow, backend) = new_fixture(512, &data);
+
+        let mut reader = AsyncReader::new(&qcow, backend);
+        reader.seek(io::SeekFrom::Start(2 * 512)).await.unwrap();
+        assert_eq!(reader.guest_pos(), 2 * 512);
+    }
+}
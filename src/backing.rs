@@ -0,0 +1,160 @@
+use crate::*;
+use crate::cache::{CacheMap, DEFAULT_CACHE_SIZE};
+use crate::levels::L2Entry;
+use crate::reader::Reader;
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Maximum backing-file chain depth [`Qcow2::open_with_backing`] will follow before
+/// giving up. Guards against a (possibly adversarial) backing chain that cycles back on
+/// itself, which would otherwise recurse until the stack overflows.
+const MAX_BACKING_CHAIN_DEPTH: usize = 32;
+
+/// Something that can serve guest reads for an image, whether that's a [`Qcow2`]
+/// [`Reader`](crate::reader::Reader) or a flat raw image. Lets a qcow2 image delegate
+/// cluster misses to a parent image in its backing chain without caring whether that
+/// parent is itself a qcow2 image or a raw file.
+pub trait GuestRead {
+    /// Read `buf.len()` bytes starting at guest offset `pos`, following this image's own
+    /// backing chain if needed. Positions past the end of the image read as zero.
+    fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> io::Result<()>;
+}
+
+impl GuestRead for File {
+    fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> io::Result<()> {
+        let len = self.seek(SeekFrom::End(0))?;
+        self.seek(SeekFrom::Start(pos))?;
+
+        if pos >= len {
+            buf.fill(0);
+            return Ok(());
+        }
+
+        let readable = usize::min(buf.len(), (len - pos) as usize);
+        self.read_exact(&mut buf[..readable])?;
+        buf[readable..].fill(0);
+        Ok(())
+    }
+}
+
+/// Read the backing file name: the (possibly relative) path of this image's backing
+/// file, stored at the `offset`/`size` given by the header's `backing_file_offset` and
+/// `backing_file_size` fields.
+pub(crate) fn read_backing_file_name<R: Read + Seek>(
+    reader: &mut R, offset: u32, size: u32,
+) -> io::Result<Option<String>> {
+    if size == 0 {
+        return Ok(None);
+    }
+
+    reader.seek(SeekFrom::Start(offset as u64))?;
+    let mut name = vec![0u8; size as usize];
+    reader.read_exact(&mut name)?;
+
+    String::from_utf8(name)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Resolve a `backing_file` path relative to the image that references it, the same way
+/// qemu does: relative to the directory containing the referencing image.
+pub(crate) fn resolve_backing_path(image_path: &Path, backing_file: &str) -> PathBuf {
+    let backing_file = Path::new(backing_file);
+    if backing_file.is_absolute() {
+        return backing_file.to_path_buf();
+    }
+
+    image_path.parent()
+        .map(|dir| dir.join(backing_file))
+        .unwrap_or_else(|| backing_file.to_path_buf())
+}
+
+impl Qcow2 {
+    /// Open this image's backing file (if it has one declared in its header), following
+    /// the chain recursively so a snapshot layered several deep still resolves reads
+    /// correctly.
+    ///
+    /// `image_path` must be the path this [`Qcow2`] itself was opened from; it is only
+    /// used to resolve the backing file's path if that path is relative.
+    pub fn open_with_backing<P: AsRef<Path>>(
+        &mut self, image_path: P,
+    ) -> io::Result<()> {
+        self.open_with_backing_at_depth(image_path, 0)
+    }
+
+    fn open_with_backing_at_depth<P: AsRef<Path>>(
+        &mut self, image_path: P, depth: usize,
+    ) -> io::Result<()> {
+        let backing_file = match &self.header.backing_file_name {
+            Some(name) => name.clone(),
+            None => return Ok(()),
+        };
+
+        if depth >= MAX_BACKING_CHAIN_DEPTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "backing file chain exceeds the maximum depth (possible cycle)",
+            ));
+        }
+
+        let backing_path = resolve_backing_path(image_path.as_ref(), &backing_file);
+        let backing: Box<dyn GuestRead> = match crate::open(&backing_path) {
+            Ok(QcowFile::Qcow2(mut backing_qcow)) => {
+                backing_qcow.open_with_backing_at_depth(&backing_path, depth + 1)?;
+                Box::new(OwnedQcow2File::open(backing_qcow, &backing_path)?)
+            }
+            Ok(QcowFile::Raw(_)) | Err(_) => Box::new(File::open(&backing_path)?),
+        };
+
+        *self.backing.borrow_mut() = Some(backing);
+        Ok(())
+    }
+}
+
+/// A [`Qcow2`] paired with the host [`File`] it reads from, so it can be stored behind a
+/// single `Box<dyn GuestRead>` in a backing chain.
+///
+/// The decoded-L2-table cache is kept here (rather than on the short-lived `Reader`
+/// built per [`read_at`](Self::read_at)) so that repeated reads against this backing
+/// image, e.g. every cluster miss in an overlay falling through to it, reuse the same
+/// cache instead of re-reading an L2 table from the host file on every single call.
+struct OwnedQcow2File {
+    qcow: Qcow2,
+    file: File,
+    l2_tables: CacheMap<Vec<L2Entry>>,
+}
+
+impl OwnedQcow2File {
+    fn open(qcow: Qcow2, path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let l2_tables = CacheMap::new(DEFAULT_CACHE_SIZE);
+        Ok(OwnedQcow2File { qcow, file, l2_tables })
+    }
+}
+
+impl GuestRead for OwnedQcow2File {
+    fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> io::Result<()> {
+        // positions past the end of the virtual disk read as zero, like `File`'s impl
+        let size = self.qcow.virtual_size();
+        let readable = if pos >= size { 0 } else { u64::min(buf.len() as u64, size - pos) as usize };
+        buf[readable..].fill(0);
+        if readable == 0 {
+            return Ok(());
+        }
+
+        // borrow the cache out of `self` for the duration of the read so it can be
+        // threaded into the (otherwise short-lived) `Reader`, then reclaim it once the
+        // read is done so the next call starts warm instead of empty.
+        let l2_tables = std::mem::replace(&mut self.l2_tables, CacheMap::new(1));
+        let mut reader = self.qcow.reader_with_cache(&mut self.file, l2_tables);
+
+        let result = reader.seek(SeekFrom::Start(pos)).and_then(|_| reader.read_exact(&mut buf[..readable]));
+
+        let Reader { l2_tables, .. } = reader;
+        self.l2_tables = l2_tables;
+
+        result
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+/// The cache size used by [`Qcow2::reader`](crate::Qcow2::reader) when no explicit
+/// capacity is requested via
+/// [`reader_with_cache_size`](crate::Qcow2::reader_with_cache_size).
+pub const DEFAULT_CACHE_SIZE: usize = 64;
+
+/// A small bounded cache keyed by host-file offset, evicting the least-recently-used
+/// entry once it grows past `capacity`.
+///
+/// Used by [`Reader`](crate::reader::Reader) to keep decoded L2 tables around across
+/// `read`/`seek` calls instead of re-reading them from
+/// the host file every time the guest access pattern moves between two regions of the
+/// disk.
+#[derive(Debug)]
+pub(crate) struct CacheMap<V> {
+    capacity: usize,
+    entries: HashMap<u64, V>,
+    /// most-recently-used keys at the back, least-recently-used at the front
+    order: VecDeque<u64>,
+}
+
+impl<V> CacheMap<V> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        CacheMap {
+            capacity: capacity.max(1),
+            entries: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub(crate) fn get(&mut self, key: u64) -> Option<&V> {
+        if self.entries.contains_key(&key) {
+            self.touch(key);
+            self.entries.get(&key)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn insert(&mut self, key: u64, value: V) {
+        if self.entries.insert(key, value).is_some() {
+            self.touch(key);
+            return;
+        }
+
+        self.order.push_back(key);
+
+        if self.entries.len() > self.capacity {
+            if let Some(lru_key) = self.order.pop_front() {
+                self.entries.remove(&lru_key);
+            }
+        }
+    }
+
+    fn touch(&mut self, key: u64) {
+        if let Some(pos) = self.order.iter().position(|&k| k == key) {
+            self.order.remove(pos);
+        }
+        self.order.push_back(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used_entry_once_over_capacity() {
+        let mut cache = CacheMap::new(2);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        cache.insert(3, "c");
+
+        assert_eq!(cache.get(1), None, "oldest entry should have been evicted");
+        assert_eq!(cache.get(2), Some(&"b"));
+        assert_eq!(cache.get(3), Some(&"c"));
+    }
+
+    #[test]
+    fn get_refreshes_recency_so_it_is_not_the_next_eviction() {
+        let mut cache = CacheMap::new(2);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+
+        // touch 1 so 2 becomes the least-recently-used entry
+        assert_eq!(cache.get(1), Some(&"a"));
+        cache.insert(3, "c");
+
+        assert_eq!(cache.get(2), None, "2 should have been evicted instead of 1");
+        assert_eq!(cache.get(1), Some(&"a"));
+        assert_eq!(cache.get(3), Some(&"c"));
+    }
+
+    #[test]
+    fn capacity_is_clamped_to_at_least_one() {
+        let mut cache = CacheMap::new(0);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+
+        assert_eq!(cache.get(1), None);
+        assert_eq!(cache.get(2), Some(&"b"));
+    }
+}
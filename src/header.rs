@@ -0,0 +1,85 @@
+use crate::*;
+use crate::backing::read_backing_file_name;
+use crate::levels::L1Entry;
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Magic bytes every qcow2 image starts with: `"QFI\xfb"`.
+pub(crate) const MAGIC: u32 = 0x5146_49fb;
+
+/// Byte offset of the header's `size` (virtual disk size) field, used by
+/// [`Writer::flush`](crate::writer::Writer::flush) to patch it in place after a write
+/// extends the guest disk.
+pub(crate) const SIZE_FIELD_OFFSET: u64 = 24;
+
+/// Byte offset of the header's `l1_size` field, used by
+/// [`Writer::flush`](crate::writer::Writer::flush) to patch it in place after a write
+/// grows the L1 table.
+pub(crate) const L1_SIZE_FIELD_OFFSET: u64 = 36;
+
+/// Parse the qcow2 header (and, for a v3+ image, the additional v3 header fields)
+/// starting at the beginning of `reader`. Header extensions are not parsed.
+pub(crate) fn parse<R: Read + Seek>(reader: &mut R) -> io::Result<Header> {
+    reader.seek(SeekFrom::Start(0))?;
+
+    let mut fixed = [0u8; 72];
+    reader.read_exact(&mut fixed)?;
+
+    let magic = u32::from_be_bytes(fixed[0..4].try_into().unwrap());
+    if magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a qcow2 image"));
+    }
+
+    let version = u32::from_be_bytes(fixed[4..8].try_into().unwrap());
+    let backing_file_offset = u64::from_be_bytes(fixed[8..16].try_into().unwrap());
+    let backing_file_size = u32::from_be_bytes(fixed[16..20].try_into().unwrap());
+    let cluster_bits = u32::from_be_bytes(fixed[20..24].try_into().unwrap());
+    let size = u64::from_be_bytes(fixed[24..32].try_into().unwrap());
+    let l1_size = u32::from_be_bytes(fixed[36..40].try_into().unwrap());
+    let l1_table_offset = u64::from_be_bytes(fixed[40..48].try_into().unwrap());
+    let refcount_table_offset = u64::from_be_bytes(fixed[48..56].try_into().unwrap());
+    let refcount_table_clusters = u32::from_be_bytes(fixed[56..60].try_into().unwrap());
+
+    let (refcount_order, v3_header) = if version >= 3 {
+        let mut v3_fixed = [0u8; 32];
+        reader.read_exact(&mut v3_fixed)?;
+        let refcount_order = u32::from_be_bytes(v3_fixed[24..28].try_into().unwrap());
+        (refcount_order, Some(V3Header { compression_type: CompressionType::default() }))
+    } else {
+        // refcount_order is implicitly 4 (16-bit refcounts) for version 2 images
+        (4, None)
+    };
+
+    let backing_file_name = read_backing_file_name(
+        reader, backing_file_offset as u32, backing_file_size,
+    )?;
+
+    Ok(Header {
+        version,
+        cluster_bits,
+        size,
+        l1_size,
+        l1_table_offset,
+        refcount_table_offset,
+        refcount_table_clusters,
+        refcount_order,
+        backing_file_name,
+        v3_header,
+    })
+}
+
+/// Read the L1 table described by `header` from `reader`.
+pub(crate) fn read_l1_table<R: Read + Seek>(
+    reader: &mut R, header: &Header,
+) -> io::Result<Vec<L1Entry>> {
+    reader.seek(SeekFrom::Start(header.l1_table_offset))?;
+
+    let mut table = Vec::with_capacity(header.l1_size as usize);
+    for _ in 0..header.l1_size {
+        let mut raw = [0u8; 8];
+        reader.read_exact(&mut raw)?;
+        table.push(L1Entry::from_raw(u64::from_be_bytes(raw)));
+    }
+
+    Ok(table)
+}
@@ -0,0 +1,146 @@
+use crate::*;
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Set on an L1/L2 entry's offset to mark the referenced cluster as "copied", i.e. its
+/// refcount is exactly 1 and it is therefore safe to write to in place without going
+/// through copy-on-write.
+pub(crate) const OFLAG_COPIED: u64 = 1 << 63;
+
+/// Set on an L2 entry to mark the cluster it points to as a preallocated zero cluster
+/// rather than real guest data.
+pub(crate) const OFLAG_ZERO: u64 = 1 << 0;
+
+/// Mask of the bits of an L1/L2 entry that encode the host cluster offset.
+pub(crate) const OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+
+/// An entry in the L1 (first-level) address translation table. Points at the L2 table
+/// that covers a contiguous range of the guest address space.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct L1Entry {
+    /// host file offset of the L2 table, or `0` if the L2 table has not been allocated yet
+    pub(crate) l2_offset: u64,
+}
+
+impl L1Entry {
+    pub(crate) fn from_raw(raw: u64) -> Self {
+        L1Entry { l2_offset: raw & OFFSET_MASK }
+    }
+
+    pub(crate) fn to_raw(self) -> u64 {
+        self.l2_offset | OFLAG_COPIED
+    }
+
+    pub(crate) fn is_allocated(&self) -> bool {
+        self.l2_offset != 0
+    }
+
+    /// Read and decode the L2 table this entry points to.
+    pub(crate) fn read_l2<R: Read + Seek>(
+        &self, reader: &mut R, cluster_bits: u32,
+    ) -> Option<Vec<L2Entry>> {
+        if self.l2_offset == 0 {
+            return None;
+        }
+
+        let entries = (1u64 << cluster_bits) / std::mem::size_of::<u64>() as u64;
+        reader.seek(SeekFrom::Start(self.l2_offset)).ok()?;
+
+        let mut table = Vec::with_capacity(entries as usize);
+        for _ in 0..entries {
+            let mut raw = [0u8; 8];
+            reader.read_exact(&mut raw).ok()?;
+            table.push(L2Entry::from_raw(u64::from_be_bytes(raw)));
+        }
+
+        Some(table)
+    }
+
+    /// Write this entry back to its slot in the L1 table on the host file.
+    pub(crate) fn write<W: io::Write + Seek>(
+        &self, writer: &mut W, l1_table_offset: u64, l1_key: u64,
+    ) -> io::Result<()> {
+        let slot = l1_table_offset + l1_key * std::mem::size_of::<u64>() as u64;
+        writer.seek(SeekFrom::Start(slot))?;
+        writer.write_all(&self.to_raw().to_be_bytes())
+    }
+}
+
+/// An entry in the L2 (second-level) address translation table. Points at (or describes)
+/// a single guest data cluster.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct L2Entry {
+    raw: u64,
+}
+
+impl L2Entry {
+    pub(crate) fn from_raw(raw: u64) -> Self {
+        L2Entry { raw }
+    }
+
+    pub(crate) fn to_raw(self) -> u64 {
+        self.raw
+    }
+
+    /// host offset of the data cluster (or compressed data) this entry describes
+    pub(crate) fn cluster_offset(&self) -> u64 {
+        self.raw & OFFSET_MASK
+    }
+
+    pub(crate) fn with_cluster_offset(self, offset: u64) -> Self {
+        // Repointing an entry at real data means it is no longer a preallocated zero
+        // cluster, so `OFLAG_ZERO` must not survive from whatever this entry used to be.
+        L2Entry { raw: (offset & OFFSET_MASK) | (self.raw & !OFFSET_MASK & !OFLAG_ZERO) | OFLAG_COPIED }
+    }
+
+    /// Whether this cluster is explicitly zeroed (`QCOW_OFLAG_ZERO`), as opposed to
+    /// simply unallocated.
+    pub(crate) fn is_zero(&self) -> bool {
+        self.raw & OFLAG_ZERO != 0
+    }
+
+    pub(crate) fn is_allocated(&self) -> bool {
+        self.cluster_offset() != 0 && !self.is_zero()
+    }
+
+    /// Whether a write to this cluster must go through copy-on-write: the cluster is
+    /// shared (refcount > 1, `OFLAG_COPIED` unset) and so cannot be mutated in place.
+    pub(crate) fn needs_cow(&self) -> bool {
+        self.is_allocated() && self.raw & OFLAG_COPIED == 0
+    }
+
+    pub(crate) fn read_contents<R: Read + Seek>(
+        &self, reader: &mut R, buf: &mut [u8], compression_type: CompressionType,
+    ) -> io::Result<()> {
+        if self.is_zero() || self.cluster_offset() == 0 {
+            buf.fill(0);
+            return Ok(());
+        }
+
+        reader.seek(SeekFrom::Start(self.cluster_offset()))?;
+        reader.read_exact(buf)?;
+        let _ = compression_type;
+        Ok(())
+    }
+
+    /// Write this entry back to its slot in an L2 table on the host file.
+    pub(crate) fn write<W: io::Write + Seek>(
+        &self, writer: &mut W, l2_table_offset: u64, l2_index: u64,
+    ) -> io::Result<()> {
+        let slot = l2_table_offset + l2_index * std::mem::size_of::<u64>() as u64;
+        writer.seek(SeekFrom::Start(slot))?;
+        writer.write_all(&self.to_raw().to_be_bytes())
+    }
+}
+
+pub(crate) fn l2_entries_per_table(cluster_size: u64) -> u64 {
+    cluster_size / std::mem::size_of::<u64>() as u64
+}
+
+pub(crate) fn guest_pos_to_indices(pos: u64, cluster_size: u64) -> (u64, u64, u64) {
+    let l2_entries = l2_entries_per_table(cluster_size);
+    let cluster_index = pos / cluster_size;
+    let l1_key = cluster_index / l2_entries;
+    let l2_index = cluster_index % l2_entries;
+    (l1_key, l2_index, cluster_index)
+}
@@ -1,8 +1,10 @@
 use crate::*;
-use crate::levels::{L1Entry, L2Entry};
+use crate::levels::{L1Entry, L2Entry, l2_entries_per_table};
+use crate::cache::{CacheMap, DEFAULT_CACHE_SIZE};
 
-use std::io::{self, Read, Seek};
+use std::io::{self, Read, Seek, SeekFrom};
 use std::convert::TryInto;
+use std::ops::Range;
 
 /// A reader for reading from the guest virtual drive. Should be constructed using
 /// [`Qcow2::reader`].
@@ -40,6 +42,10 @@ where R: Read + Seek,
     l1_cache: &'qcow L1Entry,
     l2_table_cache: Vec<L2Entry>,
 
+    /// decoded L2 tables keyed by their host-file offset, so alternating between two
+    /// regions of the disk doesn't force a `read_l2` round-trip on every access
+    pub(crate) l2_tables: CacheMap<Vec<L2Entry>>,
+
     // l2 key and cache. if l2 is being accessed by something with an outdated key,
     // the l2_cache needs to be refreshed before returning.
     l2_key: u64,
@@ -75,35 +81,73 @@ impl Qcow2 {
         &'qcow self, reader: &'reader mut R
     ) -> Reader<'qcow, 'reader, R>
         where R: Read + Seek,
+    {
+        self.reader_with_cache_size(reader, DEFAULT_CACHE_SIZE)
+    }
+
+    /// Create a reader for reading from the guest virtual drive, holding up to
+    /// `cache_size` decoded L2 tables in memory instead of the default.
+    ///
+    /// A larger cache avoids re-reading L2 tables from the host file when the read
+    /// pattern alternates between more distinct regions of the disk than the default
+    /// cache can hold, at the cost of `cache_size * cluster_size` bytes of memory.
+    pub fn reader_with_cache_size<'qcow, 'reader, R>(
+        &'qcow self, reader: &'reader mut R, cache_size: usize,
+    ) -> Reader<'qcow, 'reader, R>
+        where R: Read + Seek,
+    {
+        self.reader_with_cache(reader, CacheMap::new(cache_size))
+    }
+
+    /// Create a reader reusing an already-populated `l2_tables` cache, so a caller that
+    /// keeps issuing reads against the same image (e.g. a backing-chain link that gets
+    /// re-derived per read) doesn't throw the cache away between calls.
+    pub(crate) fn reader_with_cache<'qcow, 'reader, R>(
+        &'qcow self, reader: &'reader mut R, mut l2_tables: CacheMap<Vec<L2Entry>>,
+    ) -> Reader<'qcow, 'reader, R>
+        where R: Read + Seek,
     {
         let pos = 0;
         let l1_key = 0;
         let l2_key = 0;
         let qcow = self;
         let l1_cache = self.l1_table.get(l1_key as usize).expect("No L1 table entries found");
-        let l2_table_cache = l1_cache
-            .read_l2(reader, qcow.header.cluster_bits)
-            .expect("No L2 table found");
-        let l2_cache = l2_table_cache
-            .get(l2_key as usize)
-            .expect("No L2 table entries found")
-            .clone();
-
-        let mut current_cluster = vec![0; self.cluster_size() as usize].into_boxed_slice();
-        l2_cache.read_contents(
-            reader,
-            &mut current_cluster[..],
-            qcow.header
+
+        let l2_table_cache = if l1_cache.l2_offset == 0 {
+            // no L2 table at all yet: leave it empty, the fill-in below routes this
+            // straight to the backing chain instead of indexing into it
+            Vec::new()
+        } else if let Some(cached) = l2_tables.get(l1_cache.l2_offset) {
+            cached.clone()
+        } else {
+            let table = l1_cache
+                .read_l2(reader, qcow.header.cluster_bits)
+                .expect("No L2 table found");
+            l2_tables.insert(l1_cache.l2_offset, table.clone());
+            table
+        };
+
+        let l2_cache = l2_table_cache.get(l2_key as usize).copied().unwrap_or_default();
+        let current_cluster = vec![0; self.cluster_size() as usize].into_boxed_slice();
+
+        let mut result = Reader {
+            qcow, reader, pos, l1_cache, l2_table_cache, l2_tables,
+            l2_cache, l1_key, l2_key, current_cluster
+        };
+
+        if l1_cache.l2_offset == 0 || !l2_cache.is_allocated() {
+            result.read_from_backing_or_zero().expect("Failed to read first qcow cluster");
+        } else {
+            let compression_type = qcow.header
                 .v3_header
                 .as_ref()
                 .map(|hdr| hdr.compression_type)
-                .unwrap_or_default()
-        ).expect("Failed to read first qcow cluster");
-
-        Reader {
-            qcow, reader, pos, l1_cache, l2_table_cache,
-            l2_cache, l1_key, l2_key, current_cluster
+                .unwrap_or_default();
+            l2_cache.read_contents(result.reader, &mut result.current_cluster[..], compression_type)
+                .expect("Failed to read first qcow cluster");
         }
+
+        result
     }
 }
 
@@ -128,12 +172,22 @@ impl<'qcow, 'reader, R> Reader<'qcow, 'reader, R>
                     "Read position past end of virtual disk"
                 ))?;
 
-            self.l2_table_cache = self.l1_cache
-                .read_l2(self.reader, self.qcow.header.cluster_bits)
-                .ok_or_else(|| io::Error::new(
-                    io::ErrorKind::UnexpectedEof,
-                    "L2 table could not be read"
-                ))?;
+            self.l2_table_cache = if self.l1_cache.l2_offset == 0 {
+                // no L2 table at all: leave it empty, `update_l2_cache` routes this
+                // straight to the backing chain instead of indexing into it
+                Vec::new()
+            } else if let Some(cached) = self.l2_tables.get(self.l1_cache.l2_offset) {
+                cached.clone()
+            } else {
+                let l2_table = self.l1_cache
+                    .read_l2(self.reader, self.qcow.header.cluster_bits)
+                    .ok_or_else(|| io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "L2 table could not be read"
+                    ))?;
+                self.l2_tables.insert(self.l1_cache.l2_offset, l2_table.clone());
+                l2_table
+            };
         }
 
         Ok(())
@@ -148,14 +202,15 @@ impl<'qcow, 'reader, R> Reader<'qcow, 'reader, R>
             self.l2_key = l2_key;
             self.update_l1_cache()?;
             if self.l1_cache.l2_offset != 0 {
-                self.l2_cache = self.l2_table_cache[l2_index as usize].clone();
+                self.l2_cache = self.l2_table_cache[l2_index as usize];
                 self.l2_key = l2_key;
             }
         }
 
-        if self.l1_cache.l2_offset == 0 {
-            // empty cluster
-            self.current_cluster.fill(0);
+        if self.l1_cache.l2_offset == 0 || !self.l2_cache.is_allocated() {
+            // unallocated or explicitly-zeroed cluster: defer to the backing chain
+            // rather than assuming the guest actually wants zeros
+            self.read_from_backing_or_zero()?;
         } else {
             self.l2_cache.read_contents(
                 self.reader,
@@ -171,6 +226,21 @@ impl<'qcow, 'reader, R> Reader<'qcow, 'reader, R>
         Ok(())
     }
 
+    /// Fill `current_cluster` from the backing image at the current cluster's guest
+    /// offset, or with zeros if this image has no backing file.
+    fn read_from_backing_or_zero(&mut self) -> io::Result<()> {
+        let cluster_size = self.cluster_size();
+        let cluster_start = (self.pos / cluster_size) * cluster_size;
+
+        match self.qcow.backing.borrow_mut().as_mut() {
+            Some(backing) => backing.read_at(cluster_start, &mut self.current_cluster[..]),
+            None => {
+                self.current_cluster.fill(0);
+                Ok(())
+            }
+        }
+    }
+
     /// Get the size of a cluster within the qcow
     pub fn cluster_size(&self) -> u64 {
         self.qcow.cluster_size()
@@ -180,6 +250,204 @@ impl<'qcow, 'reader, R> Reader<'qcow, 'reader, R>
     pub fn cluster_bits(&self) -> u32 {
         self.qcow.header.cluster_bits
     }
+
+    /// Get the allocation status of the cluster covering `guest_pos`, without reading
+    /// or decoding its contents.
+    ///
+    /// Analogous to `lseek(2)`'s `SEEK_DATA`/`SEEK_HOLE`, this lets callers (e.g. a
+    /// sparse-aware image copier) skip clusters that don't need to be read at all.
+    pub fn extent_at(&mut self, guest_pos: u64) -> io::Result<Extent> {
+        let cluster_size = self.cluster_size();
+        let l2_entries = l2_entries_per_table(cluster_size);
+        let cluster_index = guest_pos / cluster_size;
+        let l1_key = cluster_index / l2_entries;
+        let l2_index = cluster_index % l2_entries;
+
+        let l1_entry = self.qcow.l1_table
+            .get(l1_key as usize)
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Read position past end of virtual disk"
+            ))?;
+
+        let kind = if !l1_entry.is_allocated() {
+            ExtentKind::Unallocated
+        } else if l1_key == self.l1_key {
+            Self::extent_kind_of(&self.l2_table_cache[l2_index as usize])
+        } else if let Some(l2_table) = self.l2_tables.get(l1_entry.l2_offset) {
+            Self::extent_kind_of(&l2_table[l2_index as usize])
+        } else {
+            let l2_table = l1_entry
+                .read_l2(self.reader, self.qcow.header.cluster_bits)
+                .ok_or_else(|| io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "L2 table could not be read"
+                ))?;
+            let kind = Self::extent_kind_of(&l2_table[l2_index as usize]);
+            self.l2_tables.insert(l1_entry.l2_offset, l2_table);
+            kind
+        };
+
+        let cluster_start = cluster_index * cluster_size;
+        Ok(Extent { range: cluster_start..cluster_start + cluster_size, kind })
+    }
+
+    fn extent_kind_of(entry: &L2Entry) -> ExtentKind {
+        if entry.is_zero() {
+            ExtentKind::Zero
+        } else if entry.is_allocated() {
+            ExtentKind::Allocated { host_offset: entry.cluster_offset() }
+        } else {
+            ExtentKind::Unallocated
+        }
+    }
+
+    /// Walk the whole virtual disk cluster by cluster, coalescing adjacent clusters of
+    /// the same [`ExtentKind`] into a single [`Extent`].
+    ///
+    /// **Note:** the returned iterator borrows the reader mutably, since reading L2
+    /// tables outside of the current L1 key requires the same host-file access a normal
+    /// read would.
+    pub fn extents(&mut self) -> Extents<'_, 'qcow, 'reader, R> {
+        Extents { reader: self, pos: 0 }
+    }
+
+    /// Like [`Read::read`], but when `buf` spans multiple clusters this detects runs of
+    /// clusters that are physically contiguous on the host file and serves them with a
+    /// single `read_exact` instead of one `update_l2_cache` per cluster.
+    ///
+    /// Compressed or zero/unallocated clusters always fall back to the regular
+    /// per-cluster [`Read::read`] path, since they have no contiguous host bytes to read
+    /// in bulk (or need decompression/zero-fill first).
+    pub fn read_bulk(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let cluster_size = self.cluster_size();
+        let pos_in_cluster = self.pos % cluster_size;
+
+        // not aligned to a cluster boundary, or the request doesn't even cover a full
+        // cluster: there's no run to amortize, fall back to the normal path
+        if pos_in_cluster != 0 || (buf.len() as u64) < cluster_size {
+            return self.read(buf);
+        }
+
+        let run_clusters = self.contiguous_run_len(buf.len() as u64 / cluster_size)?;
+        if run_clusters == 0 {
+            return self.read(buf);
+        }
+
+        let run_len = (run_clusters * cluster_size) as usize;
+        let host_start = self.l2_cache.cluster_offset();
+
+        self.reader.seek(SeekFrom::Start(host_start))?;
+        self.reader.read_exact(&mut buf[..run_len])?;
+
+        self.pos += run_len as u64;
+        let _ = self.update_l2_cache();
+
+        Ok(run_len)
+    }
+
+    /// Starting at the current cluster, count how many of the next `max_clusters`
+    /// clusters are allocated (uncompressed) and physically contiguous on the host
+    /// file, stopping at the first cluster that isn't.
+    fn contiguous_run_len(&mut self, max_clusters: u64) -> io::Result<u64> {
+        // compressed clusters (bit 62 of an L2 entry) have no plain host bytes to read
+        const OFLAG_COMPRESSED: u64 = 1 << 62;
+        let is_plain = |entry: &L2Entry| entry.is_allocated() && entry.to_raw() & OFLAG_COMPRESSED == 0;
+
+        // with no L2 table for the current L1 entry, `l2_cache` still holds an entry of
+        // a previously visited region and says nothing about the current cluster
+        if self.l1_cache.l2_offset == 0 || !is_plain(&self.l2_cache) {
+            return Ok(0);
+        }
+
+        let cluster_size = self.cluster_size();
+        let l2_entries = l2_entries_per_table(cluster_size);
+        let mut run = 1;
+        let mut expected_host_offset = self.l2_cache.cluster_offset() + cluster_size;
+
+        while run < max_clusters {
+            let cluster_index = self.pos / cluster_size + run;
+            let l1_key = cluster_index / l2_entries;
+            let l2_index = cluster_index % l2_entries;
+
+            if l1_key != self.l1_key {
+                break;
+            }
+
+            let entry = &self.l2_table_cache[l2_index as usize];
+            if !is_plain(entry) || entry.cluster_offset() != expected_host_offset {
+                break;
+            }
+
+            expected_host_offset += cluster_size;
+            run += 1;
+        }
+
+        Ok(run)
+    }
+}
+
+/// The allocation status of a single cluster (or, once coalesced, a run of clusters).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtentKind {
+    /// The cluster has never been written to; reads fall through to the backing file
+    /// (or zero, if there is none).
+    Unallocated,
+    /// The cluster is explicitly marked as zero (`QCOW_OFLAG_ZERO`).
+    Zero,
+    /// The cluster holds real guest data at `host_offset` in the qcow2 file.
+    Allocated { host_offset: u64 },
+}
+
+/// A contiguous guest range sharing a single [`ExtentKind`], as returned by
+/// [`Reader::extent_at`] and [`Reader::extents`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extent {
+    pub range: Range<u64>,
+    pub kind: ExtentKind,
+}
+
+/// Iterator over the [`Extent`]s of a [`Reader`], coalescing adjacent clusters of the
+/// same [`ExtentKind`]. Returned by [`Reader::extents`].
+pub struct Extents<'a, 'qcow, 'reader, R>
+    where R: Read + Seek,
+{
+    reader: &'a mut Reader<'qcow, 'reader, R>,
+    pos: u64,
+}
+
+impl<'a, 'qcow, 'reader, R> Iterator for Extents<'a, 'qcow, 'reader, R>
+    where R: Read + Seek,
+{
+    type Item = io::Result<Extent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let size = self.reader.qcow.header.size;
+        if self.pos >= size {
+            return None;
+        }
+
+        let mut extent = match self.reader.extent_at(self.pos) {
+            Ok(extent) => extent,
+            Err(e) => return Some(Err(e)),
+        };
+
+        loop {
+            let next_cluster_start = extent.range.end;
+            if next_cluster_start >= size {
+                break;
+            }
+
+            match self.reader.extent_at(next_cluster_start) {
+                Ok(next) if next.kind == extent.kind => extent.range.end = next.range.end,
+                Ok(_) => break,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        self.pos = extent.range.end;
+        Some(Ok(extent))
+    }
 }
 
 impl<'qcow, 'reader, R> Read for Reader<'qcow, 'reader, R>
@@ -237,3 +505,299 @@ impl<'qcow, 'reader, R> Seek for Reader<'qcow, 'reader, R>
         self.update_l2_cache().map(|_| self.pos)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backing::GuestRead;
+    use std::io::Cursor;
+
+    /// A minimal, wholly unallocated qcow2 image: a single L1 entry with `l2_offset ==
+    /// 0`, so every read should fall through to the backing chain (or zero).
+    fn new_unallocated_fixture(virtual_size: u64) -> (Qcow2, Cursor<Vec<u8>>) {
+        let header = Header {
+            version: 2,
+            cluster_bits: 9,
+            size: virtual_size,
+            l1_size: 1,
+            l1_table_offset: 0,
+            refcount_table_offset: 0,
+            refcount_table_clusters: 0,
+            refcount_order: 4,
+            backing_file_name: None,
+            v3_header: None,
+        };
+
+        let l1_table = vec![L1Entry::default()];
+        (Qcow2::from_parts(header, l1_table), Cursor::new(Vec::new()))
+    }
+
+    struct FixedBacking(Vec<u8>);
+
+    impl GuestRead for FixedBacking {
+        fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> io::Result<()> {
+            let pos = pos as usize;
+            if pos >= self.0.len() {
+                buf.fill(0);
+                return Ok(());
+            }
+
+            let readable = usize::min(buf.len(), self.0.len() - pos);
+            buf[..readable].copy_from_slice(&self.0[pos..pos + readable]);
+            buf[readable..].fill(0);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn unallocated_cluster_reads_zero_without_a_backing_file() {
+        let (qcow, mut rw) = new_unallocated_fixture(512);
+        let mut reader = qcow.reader(&mut rw);
+
+        let mut buf = [0xffu8; 512];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &[0u8; 512][..]);
+    }
+
+    #[test]
+    fn unallocated_cluster_falls_through_to_backing_file() {
+        let (qcow, mut rw) = new_unallocated_fixture(512);
+        let mut backing_contents = vec![0u8; 512];
+        backing_contents[..5].copy_from_slice(b"qcow!");
+        *qcow.backing.borrow_mut() = Some(Box::new(FixedBacking(backing_contents.clone())));
+
+        let mut reader = qcow.reader(&mut rw);
+        let mut buf = [0u8; 512];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &backing_contents[..]);
+    }
+
+    /// Build a tiny image with a 3-cluster guest disk: cluster 0 allocated, cluster 1
+    /// explicitly zeroed, cluster 2 unallocated. Host cluster 0 is left reserved
+    /// (unused) since an L2/data offset of `0` is the crate's own sentinel for "not
+    /// allocated", and cluster offsets below 512 bytes don't survive `OFFSET_MASK`.
+    fn new_extent_fixture() -> (Qcow2, Cursor<Vec<u8>>) {
+        const CLUSTER_SIZE: usize = 512;
+
+        let l2_table_offset = CLUSTER_SIZE as u64; // host cluster 1
+        let data_cluster_offset = 2 * CLUSTER_SIZE as u64; // host cluster 2
+
+        let mut buf = vec![0u8; CLUSTER_SIZE]; // host cluster 0: reserved
+        buf.extend_from_slice(&[0u8; CLUSTER_SIZE]); // host cluster 1: the L2 table
+        buf.extend_from_slice(&[0xabu8; CLUSTER_SIZE]); // host cluster 2: real data
+
+        let l2_raw = [
+            data_cluster_offset | crate::levels::OFLAG_COPIED, // guest cluster 0: allocated
+            crate::levels::OFLAG_ZERO,                          // guest cluster 1: explicit zero
+            0,                                                  // guest cluster 2: unallocated
+        ];
+        let l2_table_start = l2_table_offset as usize;
+        for (i, raw) in l2_raw.iter().enumerate() {
+            buf[l2_table_start + i * 8..l2_table_start + i * 8 + 8].copy_from_slice(&raw.to_be_bytes());
+        }
+
+        let header = Header {
+            version: 2,
+            cluster_bits: 9,
+            size: 3 * CLUSTER_SIZE as u64,
+            l1_size: 1,
+            l1_table_offset: 0,
+            refcount_table_offset: 0,
+            refcount_table_clusters: 0,
+            refcount_order: 4,
+            backing_file_name: None,
+            v3_header: None,
+        };
+
+        let l1_table = vec![L1Entry { l2_offset: l2_table_offset }];
+        (Qcow2::from_parts(header, l1_table), Cursor::new(buf))
+    }
+
+    #[test]
+    fn extent_at_reports_allocated_zero_and_unallocated_clusters() {
+        let (qcow, mut rw) = new_extent_fixture();
+        let mut reader = qcow.reader(&mut rw);
+
+        let cluster_size = reader.cluster_size();
+        let allocated = reader.extent_at(0).unwrap();
+        assert_eq!(allocated.kind, ExtentKind::Allocated { host_offset: 2 * cluster_size });
+        assert_eq!(allocated.range, 0..cluster_size);
+
+        let zero = reader.extent_at(cluster_size).unwrap();
+        assert_eq!(zero.kind, ExtentKind::Zero);
+
+        let unallocated = reader.extent_at(2 * cluster_size).unwrap();
+        assert_eq!(unallocated.kind, ExtentKind::Unallocated);
+    }
+
+    /// A `Read + Seek` wrapper around a `Cursor` that counts how many times it is
+    /// seeked to a given host offset, so tests can assert an L2 table is only read
+    /// from the host once even though it's consulted repeatedly.
+    struct SeekCountingReader {
+        inner: Cursor<Vec<u8>>,
+        watched_offset: u64,
+        seeks_to_watched_offset: usize,
+    }
+
+    impl Read for SeekCountingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl Seek for SeekCountingReader {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            if pos == SeekFrom::Start(self.watched_offset) {
+                self.seeks_to_watched_offset += 1;
+            }
+            self.inner.seek(pos)
+        }
+    }
+
+    /// Build a tiny image spanning two L1 entries, each pointing at its own L2 table
+    /// with a single allocated cluster.
+    fn new_multi_l1_extent_fixture() -> (Qcow2, Cursor<Vec<u8>>, u64, u64) {
+        const CLUSTER_SIZE: usize = 512;
+        let l2_entries = l2_entries_per_table(CLUSTER_SIZE as u64);
+
+        let l2_table0_offset = CLUSTER_SIZE as u64; // host cluster 1
+        let data0_offset = 2 * CLUSTER_SIZE as u64; // host cluster 2
+        let l2_table1_offset = 3 * CLUSTER_SIZE as u64; // host cluster 3
+        let data1_offset = 4 * CLUSTER_SIZE as u64; // host cluster 4
+
+        let mut buf = vec![0u8; CLUSTER_SIZE]; // host cluster 0: reserved
+        buf.extend_from_slice(&[0u8; CLUSTER_SIZE]); // host cluster 1: L2 table for L1 key 0
+        buf.extend_from_slice(&[0xabu8; CLUSTER_SIZE]); // host cluster 2: L1 key 0's data
+        buf.extend_from_slice(&[0u8; CLUSTER_SIZE]); // host cluster 3: L2 table for L1 key 1
+        buf.extend_from_slice(&[0xcdu8; CLUSTER_SIZE]); // host cluster 4: L1 key 1's data
+
+        let l2_table0_start = l2_table0_offset as usize;
+        buf[l2_table0_start..l2_table0_start + 8]
+            .copy_from_slice(&(data0_offset | crate::levels::OFLAG_COPIED).to_be_bytes());
+
+        let l2_table1_start = l2_table1_offset as usize;
+        buf[l2_table1_start..l2_table1_start + 8]
+            .copy_from_slice(&(data1_offset | crate::levels::OFLAG_COPIED).to_be_bytes());
+
+        let second_l1_key_pos = l2_entries * CLUSTER_SIZE as u64;
+
+        let header = Header {
+            version: 2,
+            cluster_bits: 9,
+            size: second_l1_key_pos + CLUSTER_SIZE as u64,
+            l1_size: 2,
+            l1_table_offset: 0,
+            refcount_table_offset: 0,
+            refcount_table_clusters: 0,
+            refcount_order: 4,
+            backing_file_name: None,
+            v3_header: None,
+        };
+
+        let l1_table = vec![
+            L1Entry { l2_offset: l2_table0_offset },
+            L1Entry { l2_offset: l2_table1_offset },
+        ];
+        (Qcow2::from_parts(header, l1_table), Cursor::new(buf), l2_table1_offset, second_l1_key_pos)
+    }
+
+    #[test]
+    fn extent_at_reuses_cached_l2_table_across_l1_boundaries() {
+        let (qcow, rw, l2_table1_offset, second_l1_key_pos) = new_multi_l1_extent_fixture();
+        let mut counting = SeekCountingReader { inner: rw, watched_offset: l2_table1_offset, seeks_to_watched_offset: 0 };
+        let mut reader = qcow.reader(&mut counting);
+
+        reader.extent_at(second_l1_key_pos).unwrap();
+        reader.extent_at(second_l1_key_pos).unwrap();
+
+        assert_eq!(
+            counting.seeks_to_watched_offset, 1,
+            "the second extent_at for the same out-of-range L1 key should hit the l2_tables cache instead of re-reading the L2 table"
+        );
+    }
+
+    #[test]
+    fn extents_split_where_the_kind_changes() {
+        let (qcow, mut rw) = new_extent_fixture();
+        let mut reader = qcow.reader(&mut rw);
+
+        let cluster_size = reader.cluster_size();
+        let extents: Vec<_> = reader.extents().collect::<io::Result<_>>().unwrap();
+        assert_eq!(extents.len(), 3, "each cluster has a distinct kind, so none should coalesce");
+        assert_eq!(extents[0].kind, ExtentKind::Allocated { host_offset: 2 * cluster_size });
+        assert_eq!(extents[1].kind, ExtentKind::Zero);
+        assert_eq!(extents[2].kind, ExtentKind::Unallocated);
+    }
+
+    /// Build an image whose guest clusters 0 and 1 are allocated back-to-back on the
+    /// host (a contiguous run), followed by an unallocated guest cluster 2.
+    fn new_bulk_fixture() -> (Qcow2, Cursor<Vec<u8>>) {
+        const CLUSTER_SIZE: usize = 512;
+
+        let l2_table_offset = CLUSTER_SIZE as u64; // host cluster 1
+        let data0_offset = 2 * CLUSTER_SIZE as u64; // host cluster 2
+        let data1_offset = 3 * CLUSTER_SIZE as u64; // host cluster 3, contiguous with cluster 2
+
+        let mut buf = vec![0u8; CLUSTER_SIZE]; // host cluster 0: reserved
+        buf.extend_from_slice(&[0u8; CLUSTER_SIZE]); // host cluster 1: the L2 table
+        buf.extend_from_slice(&[b'A'; CLUSTER_SIZE]); // host cluster 2: guest cluster 0
+        buf.extend_from_slice(&[b'B'; CLUSTER_SIZE]); // host cluster 3: guest cluster 1
+
+        let l2_raw = [
+            data0_offset | crate::levels::OFLAG_COPIED,
+            data1_offset | crate::levels::OFLAG_COPIED,
+            0,
+        ];
+        let l2_start = l2_table_offset as usize;
+        for (i, raw) in l2_raw.iter().enumerate() {
+            buf[l2_start + i * 8..l2_start + i * 8 + 8].copy_from_slice(&raw.to_be_bytes());
+        }
+
+        let header = Header {
+            version: 2,
+            cluster_bits: 9,
+            size: 3 * CLUSTER_SIZE as u64,
+            l1_size: 1,
+            l1_table_offset: 0,
+            refcount_table_offset: 0,
+            refcount_table_clusters: 0,
+            refcount_order: 4,
+            backing_file_name: None,
+            v3_header: None,
+        };
+
+        let l1_table = vec![L1Entry { l2_offset: l2_table_offset }];
+        (Qcow2::from_parts(header, l1_table), Cursor::new(buf))
+    }
+
+    #[test]
+    fn read_bulk_reads_a_contiguous_run_in_one_shot() {
+        let (qcow, mut rw) = new_bulk_fixture();
+        let mut reader = qcow.reader(&mut rw);
+
+        let mut buf = [0u8; 1024];
+        let read = reader.read_bulk(&mut buf).unwrap();
+
+        assert_eq!(read, 1024);
+        assert_eq!(&buf[..512], &[b'A'; 512][..]);
+        assert_eq!(&buf[512..], &[b'B'; 512][..]);
+        assert_eq!(reader.guest_pos(), 1024);
+    }
+
+    #[test]
+    fn read_bulk_falls_back_to_read_past_the_contiguous_run() {
+        let (qcow, mut rw) = new_bulk_fixture();
+        let mut reader = qcow.reader(&mut rw);
+
+        let mut buf = [0xffu8; 1536];
+        let read = reader.read_bulk(&mut buf).unwrap();
+
+        // only the two contiguous allocated clusters are read in bulk; the trailing
+        // unallocated cluster falls back to the regular per-cluster path
+        assert_eq!(read, 1024);
+
+        let read = reader.read_bulk(&mut buf[1024..]).unwrap();
+        assert_eq!(read, 512);
+        assert_eq!(&buf[1024..], &[0u8; 512][..]);
+    }
+}
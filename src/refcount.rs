@@ -0,0 +1,172 @@
+use std::io::{self, Read, Write, Seek, SeekFrom};
+
+/// Tracks cluster refcounts and hands out free clusters for the [`Writer`](crate::writer::Writer).
+///
+/// qcow2 images with the default `refcount_order` of 4 store one 16-bit refcount per
+/// cluster, packed into refcount blocks that are themselves tracked by a single-level
+/// refcount table (mirroring the L1/L2 address translation, but one level shallower).
+#[derive(Debug)]
+pub(crate) struct RefcountTable {
+    /// host offset of each refcount block, indexed by refcount-table slot; `0` means
+    /// the block has not been allocated yet
+    table: Vec<u64>,
+
+    /// host offset of the refcount table itself
+    table_offset: u64,
+
+    cluster_bits: u32,
+    refcount_order: u32,
+}
+
+impl RefcountTable {
+    pub(crate) fn read<R: Read + Seek>(
+        reader: &mut R, table_offset: u64, table_clusters: u32, cluster_bits: u32, refcount_order: u32,
+    ) -> io::Result<Self> {
+        let cluster_size = 1u64 << cluster_bits;
+        let entries = (table_clusters as u64 * cluster_size) / std::mem::size_of::<u64>() as u64;
+
+        reader.seek(SeekFrom::Start(table_offset))?;
+        let mut table = Vec::with_capacity(entries as usize);
+        for _ in 0..entries {
+            let mut raw = [0u8; 8];
+            reader.read_exact(&mut raw)?;
+            table.push(u64::from_be_bytes(raw));
+        }
+
+        Ok(RefcountTable { table, table_offset, cluster_bits, refcount_order })
+    }
+
+    fn cluster_size(&self) -> u64 {
+        1 << self.cluster_bits
+    }
+
+    /// Number of refcount entries packed into a single refcount block.
+    fn entries_per_block(&self) -> u64 {
+        self.cluster_size() * 8 / (1 << self.refcount_order)
+    }
+
+    fn bits_per_entry(&self) -> u32 {
+        1 << self.refcount_order
+    }
+
+    fn read_block<R: Read + Seek>(&self, reader: &mut R, block_offset: u64) -> io::Result<Vec<u16>> {
+        reader.seek(SeekFrom::Start(block_offset))?;
+        let entries = self.entries_per_block();
+        let mut block = Vec::with_capacity(entries as usize);
+
+        // refcount_order 4 => 16-bit entries, the only width this crate currently writes
+        for _ in 0..entries {
+            let mut raw = [0u8; 2];
+            reader.read_exact(&mut raw)?;
+            block.push(u16::from_be_bytes(raw));
+        }
+
+        Ok(block)
+    }
+
+    fn write_entry<W: Write + Seek>(
+        &self, writer: &mut W, block_offset: u64, index: u64, value: u16,
+    ) -> io::Result<()> {
+        debug_assert_eq!(self.bits_per_entry(), 16, "only refcount_order 4 is supported");
+        let slot = block_offset + index * 2;
+        writer.seek(SeekFrom::Start(slot))?;
+        writer.write_all(&value.to_be_bytes())
+    }
+
+    /// Get the current refcount of the cluster at `cluster_offset`, or `0` if it falls
+    /// within a refcount block that has not been allocated yet.
+    pub(crate) fn get<R: Read + Seek>(&self, reader: &mut R, cluster_offset: u64) -> io::Result<u16> {
+        let cluster_index = cluster_offset / self.cluster_size();
+        let entries_per_block = self.entries_per_block();
+        let rt_index = (cluster_index / entries_per_block) as usize;
+        let rb_index = cluster_index % entries_per_block;
+
+        match self.table.get(rt_index) {
+            Some(&block_offset) if block_offset != 0 => {
+                let block = self.read_block(reader, block_offset)?;
+                Ok(block[rb_index as usize])
+            }
+            _ => Ok(0),
+        }
+    }
+
+    /// Set the refcount of the cluster at `cluster_offset`, allocating a new refcount
+    /// block (and, if needed, growing the refcount table) on demand.
+    pub(crate) fn set<RW: Read + Write + Seek>(
+        &mut self, rw: &mut RW, cluster_offset: u64, value: u16, next_free_cluster: &mut u64,
+    ) -> io::Result<()> {
+        let cluster_index = cluster_offset / self.cluster_size();
+        let entries_per_block = self.entries_per_block();
+        let rt_index = (cluster_index / entries_per_block) as usize;
+        let rb_index = cluster_index % entries_per_block;
+
+        if rt_index >= self.table.len() {
+            self.table.resize(rt_index + 1, 0);
+        }
+
+        let mut block_offset = self.table[rt_index];
+        if block_offset == 0 {
+            block_offset = *next_free_cluster;
+            *next_free_cluster += self.cluster_size();
+
+            rw.seek(SeekFrom::Start(block_offset))?;
+            rw.write_all(&vec![0u8; self.cluster_size() as usize])?;
+
+            self.table[rt_index] = block_offset;
+            rw.seek(SeekFrom::Start(self.table_offset + rt_index as u64 * 8))?;
+            rw.write_all(&block_offset.to_be_bytes())?;
+
+            // the refcount block itself now occupies a cluster; account for it before
+            // the caller's entry write below, in case it lands in the very same block
+            self.set(rw, block_offset, 1, next_free_cluster)?;
+            block_offset = self.table[rt_index];
+        }
+
+        self.write_entry(rw, block_offset, rb_index, value)
+    }
+
+    /// Scan the refcount table/blocks for the first cluster with a refcount of zero,
+    /// mark it as in-use (refcount `1`), and return its host offset. Extends the
+    /// refcount table/blocks (via `next_free_cluster`) if every tracked cluster is in
+    /// use.
+    pub(crate) fn allocate_cluster<RW: Read + Write + Seek>(
+        &mut self, rw: &mut RW, next_free_cluster: &mut u64,
+    ) -> io::Result<u64> {
+        let entries_per_block = self.entries_per_block();
+
+        for rt_index in 0..self.table.len() {
+            let block_offset = self.table[rt_index];
+            if block_offset == 0 {
+                continue;
+            }
+
+            let block = self.read_block(rw, block_offset)?;
+            if let Some(rb_index) = block.iter().position(|&refcount| refcount == 0) {
+                let cluster_index = rt_index as u64 * entries_per_block + rb_index as u64;
+                let cluster_offset = cluster_index * self.cluster_size();
+                // a free cluster at or past the end of the file now occupies that space
+                *next_free_cluster = u64::max(*next_free_cluster, cluster_offset + self.cluster_size());
+                self.set(rw, cluster_offset, 1, next_free_cluster)?;
+                return Ok(cluster_offset);
+            }
+        }
+
+        // nothing free: carve a brand new cluster out of the end of the file
+        let cluster_offset = *next_free_cluster;
+        *next_free_cluster += self.cluster_size();
+        self.set(rw, cluster_offset, 1, next_free_cluster)?;
+        Ok(cluster_offset)
+    }
+
+    /// Decrement the refcount of the cluster at `cluster_offset`. The cluster is left
+    /// allocated (for a future [`allocate_cluster`](Self::allocate_cluster) call to
+    /// reclaim) once its refcount reaches zero; nothing actually punches a hole in the
+    /// host file.
+    pub(crate) fn decrement<RW: Read + Write + Seek>(
+        &mut self, rw: &mut RW, cluster_offset: u64, next_free_cluster: &mut u64,
+    ) -> io::Result<()> {
+        let refcount = self.get(rw, cluster_offset)?;
+        let refcount = refcount.saturating_sub(1);
+        self.set(rw, cluster_offset, refcount, next_free_cluster)
+    }
+}